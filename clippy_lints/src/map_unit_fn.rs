@@ -1,12 +1,15 @@
 use crate::utils::paths;
-use crate::utils::{in_macro, iter_input_pats, match_type, method_chain_args, snippet, span_lint_and_then};
+use crate::utils::{
+    implements_trait, in_macro, iter_input_pats, match_type, method_chain_args, snippet, span_lint_and_then,
+};
 use if_chain::if_chain;
 use rustc::hir;
+use rustc::hir::intravisit::{self, NestedVisitorMap, Visitor};
 use rustc::lint::{LateContext, LateLintPass, LintArray, LintPass};
 use rustc::ty::{self, Ty};
 use rustc::{declare_lint_pass, declare_tool_lint};
 use rustc_errors::Applicability;
-use syntax::source_map::Span;
+use syntax::source_map::{BytePos, Span};
 
 declare_clippy_lint! {
     /// **What it does:** Checks for usage of `option.map(f)` where f is a function
@@ -74,7 +77,38 @@ declare_clippy_lint! {
     "using `result.map(f)`, where f is a function or closure that returns ()"
 }
 
-declare_lint_pass!(MapUnit => [OPTION_MAP_UNIT_FN, RESULT_MAP_UNIT_FN]);
+declare_clippy_lint! {
+    /// **What it does:** Checks for usage of `iterator.map(f)` where f is a function
+    /// or closure that returns the unit type `()`.
+    ///
+    /// **Why is this bad?** `Iterator::map` is lazy, so a unit-returning `f` is
+    /// either a no-op (if the result is never consumed) or is being used to run
+    /// `f` for its side effects, which is more clearly written as `for_each` or a
+    /// `for` loop.
+    ///
+    /// **Known problems:** None.
+    ///
+    /// **Example:**
+    ///
+    /// ```rust
+    /// let x: Vec<&str> = do_stuff();
+    /// x.iter().map(log_err_msg);
+    /// x.iter().map(|msg| log_err_msg(format_msg(msg)))
+    /// ```
+    ///
+    /// The correct use would be:
+    ///
+    /// ```rust
+    /// let x: Vec<&str> = do_stuff();
+    /// x.iter().for_each(log_err_msg);
+    /// x.iter().for_each(|msg| log_err_msg(format_msg(msg)))
+    /// ```
+    pub MAP_UNIT_ITER,
+    complexity,
+    "using `iterator.map(f)`, where f is a function or closure that returns ()"
+}
+
+declare_lint_pass!(MapUnit => [OPTION_MAP_UNIT_FN, RESULT_MAP_UNIT_FN, MAP_UNIT_ITER]);
 
 fn is_unit_type(ty: Ty<'_>) -> bool {
     match ty.sty {
@@ -84,12 +118,16 @@ fn is_unit_type(ty: Ty<'_>) -> bool {
     }
 }
 
+/// A "unit function" is a single-argument function item that returns `()`;
+/// closures are not matched here (see `unit_closure`). The arity check matters for the suggestion: we rewrite `f(...)` to
+/// `f(name)`, which is only sound if `f` takes exactly the one argument `map`
+/// would have passed it.
 fn is_unit_function(cx: &LateContext<'_, '_>, expr: &hir::Expr) -> bool {
     let ty = cx.tables.expr_ty(expr);
 
     if let ty::FnDef(id, _) = ty.sty {
         if let Some(fn_type) = cx.tcx.fn_sig(id).no_bound_vars() {
-            return is_unit_type(fn_type.output());
+            return fn_type.inputs().len() == 1 && is_unit_type(fn_type.output());
         }
     }
     false
@@ -99,6 +137,15 @@ fn is_unit_expression(cx: &LateContext<'_, '_>, expr: &hir::Expr) -> bool {
     is_unit_type(cx.tables.expr_ty(expr))
 }
 
+/// Checks whether `ty` implements the `Iterator` trait, for distinguishing
+/// `iterator.map(f)` from `Option`/`Result`'s `map`.
+fn is_iterator_type(cx: &LateContext<'_, '_>, ty: Ty<'_>) -> bool {
+    cx.tcx
+        .lang_items()
+        .iterator_trait()
+        .map_or(false, |iter_id| implements_trait(cx, ty, iter_id, &[]))
+}
+
 /// The expression inside a closure may or may not have surrounding braces and
 /// semicolons, which causes problems when generating a suggestion. Given an
 /// expression that evaluates to '()' or '!', recursively remove useless braces
@@ -144,6 +191,54 @@ fn reduce_unit_expression<'a>(cx: &LateContext<'_, '_>, expr: &'a hir::Expr) ->
     }
 }
 
+/// True if `expr` contains a `return` that isn't shadowed by a nested
+/// closure or item (which would have its own, unrelated return boundary). A
+/// `return` inside a spliced-out closure body would no longer exit just the
+/// closure once moved into an `if let`/`for_each` block, so callers must not
+/// treat such a body as safe to splice verbatim.
+fn contains_return(expr: &hir::Expr) -> bool {
+    struct RetVisitor {
+        found: bool,
+    }
+
+    impl<'tcx> Visitor<'tcx> for RetVisitor {
+        fn nested_visit_map(&mut self) -> NestedVisitorMap<'_, 'tcx> {
+            NestedVisitorMap::None
+        }
+
+        fn visit_expr(&mut self, expr: &'tcx hir::Expr) {
+            if let hir::ExprKind::Ret(_) = expr.node {
+                self.found = true;
+            }
+            if !self.found {
+                intravisit::walk_expr(self, expr);
+            }
+        }
+    }
+
+    let mut visitor = RetVisitor { found: false };
+    visitor.visit_expr(expr);
+    visitor.found
+}
+
+/// For a closure body that `reduce_unit_expression` couldn't collapse to a
+/// single span (typically a block with more than one statement), returns the
+/// span of the block's interior, between its braces, so it can be spliced
+/// into a suggestion verbatim, newlines, indentation and all.
+///
+/// Returns `None` if the body contains a `return`, since splicing it out of
+/// the closure that gives it meaning would silently change what it exits.
+fn block_body_span(expr: &hir::Expr) -> Option<Span> {
+    if contains_return(expr) {
+        return None;
+    }
+    if let hir::ExprKind::Block(ref block, _) = expr.node {
+        let span = block.span;
+        return Some(span.with_lo(span.lo() + BytePos(1)).with_hi(span.hi() - BytePos(1)));
+    }
+    None
+}
+
 fn unit_closure<'a, 'tcx>(cx: &LateContext<'a, 'tcx>, expr: &'a hir::Expr) -> Option<(&'tcx hir::Arg, &'a hir::Expr)> {
     if let hir::ExprKind::Closure(_, ref decl, inner_expr_id, _, _) = expr.node {
         let body = cx.tcx.hir().body(inner_expr_id);
@@ -161,17 +256,25 @@ fn unit_closure<'a, 'tcx>(cx: &LateContext<'a, 'tcx>, expr: &'a hir::Expr) -> Op
     None
 }
 
-/// Builds a name for the let binding variable (`var_arg`)
+/// Builds a name for the let binding variable (`var_arg`). The result is
+/// used in the suggestion, so it must be a real identifier, not a `_`
+/// wildcard, and it must actually introduce a fresh binding rather than
+/// accidentally naming something that already exists.
 ///
 /// `x.field` => `x_field`
-/// `y` => `_y`
+/// `y`, where `y` is a local variable => `y`
 ///
-/// Anything else will return `_`.
+/// Anything else (including a bare path to a `const`/`static`, which would
+/// otherwise parse as a constant pattern instead of a fresh binding) returns
+/// `a`.
 fn let_binding_name(cx: &LateContext<'_, '_>, var_arg: &hir::Expr) -> String {
     match &var_arg.node {
-        hir::ExprKind::Field(_, _) => snippet(cx, var_arg.span, "_").replace(".", "_"),
-        hir::ExprKind::Path(_) => format!("_{}", snippet(cx, var_arg.span, "")),
-        _ => "_".to_string(),
+        hir::ExprKind::Field(_, _) => snippet(cx, var_arg.span, "a").replace(".", "_"),
+        hir::ExprKind::Path(ref qpath) => match cx.tables.qpath_res(qpath, var_arg.hir_id) {
+            hir::def::Res::Local(_) => snippet(cx, var_arg.span, "a").to_string(),
+            _ => "a".to_string(),
+        },
+        _ => "a".to_string(),
     }
 }
 
@@ -182,13 +285,17 @@ fn suggestion_msg(function_type: &str, map_type: &str) -> String {
     )
 }
 
-fn lint_map_unit_fn(cx: &LateContext<'_, '_>, stmt: &hir::Stmt, expr: &hir::Expr, map_args: &[hir::Expr]) {
+fn lint_map_unit_fn(cx: &LateContext<'_, '_>, suggestion_span: Span, expr: &hir::Expr, map_args: &[hir::Expr]) {
     let var_arg = &map_args[0];
+    let var_ty = cx.tables.expr_ty(var_arg);
 
-    let (map_type, variant, lint) = if match_type(cx, cx.tables.expr_ty(var_arg), &paths::OPTION) {
+    let (map_type, variant, lint) = if match_type(cx, var_ty, &paths::OPTION) {
         ("Option", "Some", OPTION_MAP_UNIT_FN)
-    } else if match_type(cx, cx.tables.expr_ty(var_arg), &paths::RESULT) {
+    } else if match_type(cx, var_ty, &paths::RESULT) {
         ("Result", "Ok", RESULT_MAP_UNIT_FN)
+    } else if is_iterator_type(cx, var_ty) {
+        lint_map_unit_iter(cx, suggestion_span, expr, map_args);
+        return;
     } else {
         return;
     };
@@ -196,16 +303,17 @@ fn lint_map_unit_fn(cx: &LateContext<'_, '_>, stmt: &hir::Stmt, expr: &hir::Expr
 
     if is_unit_function(cx, fn_arg) {
         let msg = suggestion_msg("function", map_type);
+        let name = let_binding_name(cx, var_arg);
         let suggestion = format!(
-            "if let {0}({1}) = {2} {{ {3}(...) }}",
+            "if let {0}({1}) = {2} {{ {3}({1}) }}",
             variant,
-            let_binding_name(cx, var_arg),
+            name,
             snippet(cx, var_arg.span, "_"),
             snippet(cx, fn_arg.span, "_")
         );
 
         span_lint_and_then(cx, lint, expr.span, &msg, |db| {
-            db.span_suggestion(stmt.span, "try this", suggestion, Applicability::Unspecified);
+            db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::MachineApplicable);
         });
     } else if let Some((binding, closure_expr)) = unit_closure(cx, fn_arg) {
         let msg = suggestion_msg("closure", map_type);
@@ -220,11 +328,23 @@ fn lint_map_unit_fn(cx: &LateContext<'_, '_>, stmt: &hir::Stmt, expr: &hir::Expr
                     snippet(cx, reduced_expr_span, "_")
                 );
                 db.span_suggestion(
-                    stmt.span,
+                    suggestion_span,
                     "try this",
                     suggestion,
                     Applicability::MachineApplicable, // snippet
                 );
+            } else if let Some(block_body_span) = block_body_span(closure_expr) {
+                // `reduce_unit_expression` gives up on multi-statement closure
+                // bodies, but we can still keep the fix machine-applicable by
+                // splicing the whole brace-delimited body in verbatim.
+                let suggestion = format!(
+                    "if let {0}({1}) = {2} {{{3}}}",
+                    variant,
+                    snippet(cx, binding.pat.span, "_"),
+                    snippet(cx, var_arg.span, "_"),
+                    snippet(cx, block_body_span, "..")
+                );
+                db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::MachineApplicable);
             } else {
                 let suggestion = format!(
                     "if let {0}({1}) = {2} {{ ... }}",
@@ -232,22 +352,105 @@ fn lint_map_unit_fn(cx: &LateContext<'_, '_>, stmt: &hir::Stmt, expr: &hir::Expr
                     snippet(cx, binding.pat.span, "_"),
                     snippet(cx, var_arg.span, "_")
                 );
-                db.span_suggestion(stmt.span, "try this", suggestion, Applicability::Unspecified);
+                db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::Unspecified);
+            }
+        });
+    }
+}
+
+/// Like `lint_map_unit_fn`, but for `iterator.map(f)`. `Iterator::map` is lazy,
+/// so a unit-returning `f` either does nothing at all (if the adaptor is never
+/// consumed) or is relying on laziness to run side effects, neither of which
+/// is obvious at the call site.
+fn lint_map_unit_iter(cx: &LateContext<'_, '_>, suggestion_span: Span, expr: &hir::Expr, map_args: &[hir::Expr]) {
+    let var_arg = &map_args[0];
+    let fn_arg = &map_args[1];
+
+    if is_unit_function(cx, fn_arg) {
+        let msg = "called `map(f)` on an iterator with `f` returning `()`; this `map` call is lazy and never runs, use `for_each` or a `for` loop instead";
+        span_lint_and_then(cx, MAP_UNIT_ITER, expr.span, msg, |db| {
+            let suggestion = format!(
+                "{}.for_each({});",
+                snippet(cx, var_arg.span, "_"),
+                snippet(cx, fn_arg.span, "_")
+            );
+            db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::MachineApplicable);
+        });
+    } else if let Some((binding, closure_expr)) = unit_closure(cx, fn_arg) {
+        let msg = "called `map(f)` on an iterator where `f` is a unit closure; this `map` call is lazy and never runs, use `for_each` or a `for` loop instead";
+        span_lint_and_then(cx, MAP_UNIT_ITER, expr.span, msg, |db| {
+            if let Some(reduced_expr_span) = reduce_unit_expression(cx, closure_expr) {
+                let suggestion = format!(
+                    "{}.for_each(|{}| {});",
+                    snippet(cx, var_arg.span, "_"),
+                    snippet(cx, binding.pat.span, "_"),
+                    snippet(cx, reduced_expr_span, "_")
+                );
+                db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::MachineApplicable);
+            } else if let Some(block_body_span) = block_body_span(closure_expr) {
+                let suggestion = format!(
+                    "{}.for_each(|{}| {{{}}});",
+                    snippet(cx, var_arg.span, "_"),
+                    snippet(cx, binding.pat.span, "_"),
+                    snippet(cx, block_body_span, "..")
+                );
+                db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::MachineApplicable);
+            } else {
+                let suggestion = format!(
+                    "{}.for_each(|{}| {{ ... }});",
+                    snippet(cx, var_arg.span, "_"),
+                    snippet(cx, binding.pat.span, "_")
+                );
+                db.span_suggestion(suggestion_span, "try this", suggestion, Applicability::Unspecified);
             }
         });
     }
 }
 
+/// Peels through a chain of statement-less blocks to their innermost tail
+/// expression, e.g. `{ { x.map(f) } }` => `x.map(f)`. A `map` call nested
+/// this way is discarded exactly as much as a bare one is, since the braces
+/// contribute no statements of their own for it to be bound or consumed by.
+fn peel_block_tail(mut expr: &hir::Expr) -> &hir::Expr {
+    while let hir::ExprKind::Block(ref block, _) = expr.node {
+        if !block.stmts.is_empty() {
+            break;
+        }
+        match block.expr {
+            Some(ref tail) => expr = tail,
+            None => break,
+        }
+    }
+    expr
+}
+
 impl<'a, 'tcx> LateLintPass<'a, 'tcx> for MapUnit {
     fn check_stmt(&mut self, cx: &LateContext<'_, '_>, stmt: &hir::Stmt) {
         if in_macro(stmt.span) {
             return;
         }
 
-        if let hir::StmtKind::Semi(ref expr) = stmt.node {
-            if let Some(arglists) = method_chain_args(expr, &["map"]) {
-                lint_map_unit_fn(cx, stmt, expr, arglists[0]);
-            }
+        match stmt.node {
+            hir::StmtKind::Semi(ref expr) => {
+                let expr = peel_block_tail(expr);
+                if let Some(arglists) = method_chain_args(expr, &["map"]) {
+                    lint_map_unit_fn(cx, stmt.span, expr, arglists[0]);
+                }
+            },
+            // `let _ = x.map(f);` discards the result just as explicitly as a
+            // bare `x.map(f);` statement does.
+            hir::StmtKind::Local(ref local) => {
+                if_chain! {
+                    if let hir::PatKind::Wild = local.pat.node;
+                    if let Some(ref init) = local.init;
+                    let init = peel_block_tail(init);
+                    if let Some(arglists) = method_chain_args(init, &["map"]);
+                    then {
+                        lint_map_unit_fn(cx, stmt.span, init, arglists[0]);
+                    }
+                }
+            },
+            hir::StmtKind::Item(..) | hir::StmtKind::Expr(..) => {},
         }
     }
 }